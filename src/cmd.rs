@@ -10,8 +10,14 @@ pub enum Command {
     Debug(String),
     #[command(description = "link your mastodon account")]
     Auth(String),
-    #[command(description = "unlink your mastodon account")]
-    Revoke,
-    #[command(description = "post the message you replied to mastodon")]
-    Post,
+    #[command(description = "unlink a mastodon account, e.g. `/revoke 8h2Kq1` (blank for the default)")]
+    Revoke(String),
+    #[command(description = "post the message you replied to mastodon, optionally to a specific account")]
+    Post(String),
+    #[command(
+        description = "view or update your posting defaults, e.g. `visibility=unlisted language=es`"
+    )]
+    Settings(String),
+    #[command(description = "list your linked mastodon accounts and pick the default")]
+    Accounts(String),
 }
@@ -1,41 +1,179 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use anyhow::anyhow;
+use axum::{
+    extract::{Query, State},
+    response::Html,
+    routing::get,
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use crypto_secretbox::{
+    aead::{rand_core::RngCore, Aead, KeyInit, OsRng},
+    Key, Nonce, XSalsa20Poly1305,
+};
 use mastodon_async::{prelude::*, registration::Registered, scopes, Language, Result as MResult};
+use rand::distributions::{Alphanumeric, DistString};
+use serde::Deserialize;
 use serde_json as json;
 use spdlog::prelude::*;
+use sqids::Sqids;
 use teloxide::types::UserId;
 
 use crate::{config, InstanceState};
 
+/// How long a `/auth` attempt stays valid while waiting for the user to
+/// complete the Mastodon-hosted authorization page.
+const PENDING_AUTH_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// An in-flight `/auth` attempt, keyed by its `state` token, waiting for the
+/// local callback server to receive the authorization code. Stored in
+/// `InstanceState`, which lives at the crate root, so this needs to be
+/// visible outside this module.
+pub(crate) struct PendingAuth {
+    reg: Registered,
+    tg_user_id: UserId,
+    expires_at: Instant,
+}
+
+#[derive(Deserialize)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: String,
+}
+
+/// Length in bytes of the random nonce prepended to every encrypted record.
+const NONCE_LEN: usize = 24;
+
+/// Returned when stored login data can't be decrypted (wrong/rotated key or a
+/// failed MAC check), so the caller can prompt the user to `/auth` again
+/// instead of propagating a raw crypto error.
+#[derive(Debug)]
+pub struct ReauthRequired;
+
+impl std::fmt::Display for ReauthRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "stored credentials could not be verified, re-authentication required")
+    }
+}
+
+impl std::error::Error for ReauthRequired {}
+
 pub struct Client {
     inst_state: Arc<InstanceState>,
 }
 
+/// Authorization link handed back to the `/auth` command. When a public
+/// callback URL is configured, completing it in the browser finishes the
+/// login automatically; otherwise the user still needs to paste the code
+/// back with a second `/auth <code>` (the out-of-band fallback).
+pub struct AuthStart {
+    pub authorize_url: String,
+    pub automatic: bool,
+}
+
 impl Client {
     pub fn new(inst_state: Arc<InstanceState>) -> Self {
         Self { inst_state }
     }
 
-    pub async fn login(&self, tg_user_id: UserId) -> anyhow::Result<LoginUser> {
-        let login_user = self
-            .load_login_user(tg_user_id)
-            .await
-            .map_err(|err| anyhow!("failed to query user login data: {err}"))?;
+    /// Resolves the account to post as: the one named by `handle`, or the
+    /// user's default account when `handle` is `None`.
+    pub async fn login(
+        &self,
+        tg_user_id: UserId,
+        handle: Option<&str>,
+    ) -> anyhow::Result<LoginUser> {
+        let login_user = match handle {
+            Some(handle) => self.load_login_user_by_handle(tg_user_id, handle).await,
+            None => self.load_default_login_user(tg_user_id).await,
+        }
+        .map_err(|err| anyhow!("failed to query user login data: {err}"))?;
         Ok(login_user)
     }
 
-    pub async fn auth_step_1(&self, domain: impl Into<String>) -> MResult<Registered> {
-        let registration = Registration::new(domain)
+    /// Lists every Mastodon account linked to this Telegram user.
+    pub async fn list(&self, tg_user_id: UserId) -> anyhow::Result<Vec<LoginUser>> {
+        self.list_login_users(tg_user_id).await
+    }
+
+    /// Marks `handle` as the default account used when `/post` is given no
+    /// explicit handle. Errors if `handle` doesn't resolve to one of this
+    /// user's own accounts.
+    pub async fn set_default(&self, tg_user_id: UserId, handle: impl AsRef<str>) -> anyhow::Result<()> {
+        let handle = handle.as_ref();
+        let account_id = decode_handle(handle)?;
+        let tg_user_id_num = tg_user_id.0 as i64;
+
+        // The `EXISTS` guard makes sure `account_id` actually belongs to
+        // `tg_user_id`; without it a stale/foreign handle would still match
+        // every row of `WHERE tg_user_id = ?1` and silently clear
+        // `is_default` for all of them.
+        let result = sqlx::query!(
+            r#"
+UPDATE login_users
+SET is_default = ( id = ?2 )
+WHERE tg_user_id = ?1
+  AND EXISTS ( SELECT 1 FROM login_users WHERE tg_user_id = ?1 AND id = ?2 )
+        "#,
+            tg_user_id_num,
+            account_id,
+        )
+        .execute(self.inst_state.db.pool())
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(anyhow!("unknown account handle '{handle}'"));
+        }
+
+        Ok(())
+    }
+
+    pub async fn auth_step_1(
+        &self,
+        domain: impl Into<String>,
+        tg_user_id: UserId,
+    ) -> MResult<AuthStart> {
+        let redirect_uri = config::oauth_callback_base_url()
+            .map(|base| format!("{}/oauth/callback", base.trim_end_matches('/')));
+
+        let mut registration = Registration::new(domain)
             .client_name(config::PACKAGE.name)
-            .scopes(Scopes::write(scopes::Write::Statuses))
-            .build()
-            .await?;
+            .scopes(Scopes::write(scopes::Write::Statuses));
+        if let Some(redirect_uri) = &redirect_uri {
+            registration = registration.redirect_uri(redirect_uri);
+        }
+        let registration = registration.build().await?;
 
         // Make sure the url is not `None` so that we can directly unwrap it later
-        registration.authorize_url()?;
+        let mut authorize_url = registration.authorize_url()?;
+
+        let automatic = match redirect_uri {
+            Some(_) => {
+                let state = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+                self.prune_expired_pending_auths();
+                self.inst_state.oauth_pending.lock().unwrap().insert(
+                    state.clone(),
+                    PendingAuth {
+                        reg: registration,
+                        tg_user_id,
+                        expires_at: Instant::now() + PENDING_AUTH_TTL,
+                    },
+                );
+                authorize_url = format!("{authorize_url}&state={state}");
+                true
+            }
+            None => false,
+        };
 
-        Ok(registration)
+        Ok(AuthStart {
+            authorize_url,
+            automatic,
+        })
     }
 
     pub async fn auth_step_2(
@@ -44,36 +182,187 @@ impl Client {
         tg_user_id: UserId,
         auth_code: impl AsRef<str>,
     ) -> anyhow::Result<LoginUser> {
-        let login_user = LoginUser {
-            inst: reg.complete(auth_code.as_ref()).await?,
-            tg_user_id,
-        };
-        self.save_login_user(tg_user_id, &login_user)
+        let inst = reg.complete(auth_code.as_ref()).await?;
+
+        let (account_id, is_default) = self
+            .save_login_user(tg_user_id, &inst.data)
             .await
             .map_err(|err| anyhow!("failed to save user login data: {err}"))?;
-        Ok(login_user)
+
+        Ok(LoginUser {
+            inst,
+            tg_user_id,
+            account_id,
+            handle: encode_handle(account_id)?,
+            is_default,
+        })
     }
 
     pub async fn revoke(&self, login_user: &LoginUser) -> anyhow::Result<()> {
-        self.delete_login_user(login_user.tg_user_id).await
+        self.delete_login_user(login_user.account_id).await
+    }
+
+    pub async fn settings(&self, tg_user_id: UserId) -> anyhow::Result<PostOptions> {
+        self.load_settings(tg_user_id).await
+    }
+
+    /// Applies whitespace-separated `key=value` updates (e.g.
+    /// `visibility=unlisted cw=spoilers`) on top of the user's current
+    /// settings and persists the result.
+    pub async fn update_settings(
+        &self,
+        tg_user_id: UserId,
+        raw: impl AsRef<str>,
+    ) -> anyhow::Result<PostOptions> {
+        let mut settings = self.load_settings(tg_user_id).await?;
+
+        for pair in raw.as_ref().split_whitespace() {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected `key=value`, got '{pair}'"))?;
+
+            match key {
+                "visibility" => settings.visibility = parse_visibility(value)?,
+                "language" => settings.language = value.to_string(),
+                "cw" | "spoiler" => {
+                    settings.spoiler_text =
+                        (!value.eq_ignore_ascii_case("off")).then(|| value.to_string())
+                }
+                "sensitive" => {
+                    settings.sensitive = value
+                        .parse()
+                        .map_err(|_| anyhow!("'sensitive' must be 'true' or 'false'"))?
+                }
+                other => return Err(anyhow!("unknown setting '{other}'")),
+            }
+        }
+
+        self.save_settings(tg_user_id, &settings).await?;
+        Ok(settings)
+    }
+
+    /// Builds the `GET /oauth/callback` route to be merged into the bot's
+    /// HTTP server when a public callback URL is configured.
+    pub fn oauth_router(&self) -> Router {
+        Router::new()
+            .route("/oauth/callback", get(oauth_callback))
+            .with_state(Arc::clone(&self.inst_state))
+    }
+
+    fn prune_expired_pending_auths(&self) {
+        let now = Instant::now();
+        self.inst_state
+            .oauth_pending
+            .lock()
+            .unwrap()
+            .retain(|_, pending| pending.expires_at > now);
+    }
+}
+
+async fn oauth_callback(
+    State(inst_state): State<Arc<InstanceState>>,
+    Query(query): Query<CallbackQuery>,
+) -> Html<&'static str> {
+    let pending = inst_state.oauth_pending.lock().unwrap().remove(&query.state);
+
+    let Some(pending) = pending else {
+        warn!("oauth callback received for unknown or expired state '{}'", query.state);
+        return Html("<p>This authorization link has expired, please try <code>/auth</code> again.</p>");
+    };
+
+    if pending.expires_at < Instant::now() {
+        warn!("oauth callback received for expired state '{}'", query.state);
+        return Html("<p>This authorization link has expired, please try <code>/auth</code> again.</p>");
+    }
+
+    let Some(code) = query.code else {
+        warn!("oauth callback for tg user '{}' was missing the authorization code", pending.tg_user_id);
+        return Html("<p>Mastodon did not return an authorization code, please try again.</p>");
+    };
+
+    let client = Client::new(inst_state);
+    match client.auth_step_2(&pending.reg, pending.tg_user_id, code).await {
+        Ok(login_user) => {
+            info!("tg user '{}' completed automatic oauth login to '{}'", pending.tg_user_id, login_user.domain());
+            Html("<p>You're linked! You can close this tab and go back to Telegram.</p>")
+        }
+        Err(err) => {
+            warn!("tg user '{}' failed to complete automatic oauth login: {err}", pending.tg_user_id);
+            Html("<p>Something went wrong completing your login, please try <code>/auth</code> again.</p>")
+        }
     }
 }
 
 impl Client {
-    async fn save_login_user(
+    /// Inserts a new linked account row, returning its surrogate account id
+    /// and whether it became the user's default (i.e. their first account).
+    async fn save_login_user(&self, tg_user_id: UserId, data: &Data) -> anyhow::Result<(i64, bool)> {
+        let tg_user_id_num = tg_user_id.0 as i64;
+        let login_user_data = encrypt_data(data)?;
+
+        // The "is this the first account" check and the insert run in one
+        // transaction so two concurrent logins for the same tg_user_id (e.g.
+        // the automatic OAuth callback racing a manual `/auth <code>` retry)
+        // can't both see `count == 0` and both insert `is_default = true`.
+        // SQLite enforces that by making the loser's commit fail with
+        // "database is locked" rather than letting it race silently, so
+        // retry once on that specific error before giving up.
+        match self
+            .try_save_login_user(tg_user_id_num, &login_user_data)
+            .await
+        {
+            Ok(result) => Ok(result),
+            Err(err) if is_database_busy(&err) => {
+                self.try_save_login_user(tg_user_id_num, &login_user_data)
+                    .await
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn try_save_login_user(
         &self,
-        tg_user_id: UserId,
-        login_user: &LoginUser,
-    ) -> anyhow::Result<()> {
-        let (tg_user_id, login_user_data) = (tg_user_id.0 as i64, login_user.serialize());
+        tg_user_id_num: i64,
+        login_user_data: &str,
+    ) -> anyhow::Result<(i64, bool)> {
+        let mut txn = self.inst_state.db.pool().begin().await?;
+
+        let existing = sqlx::query!(
+            r#"SELECT COUNT(*) as count FROM login_users WHERE tg_user_id = ?1"#,
+            tg_user_id_num,
+        )
+        .fetch_one(&mut *txn)
+        .await?;
+        let is_default = existing.count == 0;
+
+        let inserted = sqlx::query!(
+            r#"
+INSERT INTO login_users ( tg_user_id, mastodon_async_data, is_default )
+VALUES ( ?1, ?2, ?3 )
+        "#,
+            tg_user_id_num,
+            login_user_data,
+            is_default,
+        )
+        .execute(&mut *txn)
+        .await?;
+
+        txn.commit().await?;
+
+        Ok((inserted.last_insert_rowid(), is_default))
+    }
+
+    async fn reencrypt_login_user(&self, account_id: i64, data: &Data) -> anyhow::Result<()> {
+        let login_user_data = encrypt_data(data)?;
 
         sqlx::query!(
             r#"
-INSERT OR REPLACE INTO login_users ( tg_user_id, mastodon_async_data )
-VALUES ( ?1, ?2 )
+UPDATE login_users
+SET mastodon_async_data = ?2
+WHERE id = ?1
         "#,
-            tg_user_id,
-            login_user_data
+            account_id,
+            login_user_data,
         )
         .execute(self.inst_state.db.pool())
         .await?;
@@ -81,33 +370,176 @@ VALUES ( ?1, ?2 )
         Ok(())
     }
 
-    async fn load_login_user(&self, tg_user_id: UserId) -> anyhow::Result<LoginUser> {
+    async fn load_login_user_by_handle(
+        &self,
+        tg_user_id: UserId,
+        handle: &str,
+    ) -> anyhow::Result<LoginUser> {
+        let account_id = decode_handle(handle)?;
+        let tg_user_id_num = tg_user_id.0 as i64;
+
+        let record = sqlx::query!(
+            r#"
+SELECT id, mastodon_async_data, is_default
+FROM login_users
+WHERE tg_user_id = ?1 AND id = ?2
+        "#,
+            tg_user_id_num,
+            account_id,
+        )
+        .fetch_one(self.inst_state.db.pool())
+        .await?;
+
+        self.finish_loading_login_user(
+            record.id,
+            record.mastodon_async_data,
+            record.is_default,
+            tg_user_id,
+        )
+        .await
+    }
+
+    async fn load_default_login_user(&self, tg_user_id: UserId) -> anyhow::Result<LoginUser> {
         let tg_user_id_num = tg_user_id.0 as i64;
 
         let record = sqlx::query!(
             r#"
-SELECT mastodon_async_data
+SELECT id, mastodon_async_data, is_default
 FROM login_users
 WHERE tg_user_id = ?1
+ORDER BY is_default DESC, id ASC
+LIMIT 1
         "#,
             tg_user_id_num,
         )
         .fetch_one(self.inst_state.db.pool())
         .await?;
 
-        LoginUser::deserialize(record.mastodon_async_data, tg_user_id)
+        self.finish_loading_login_user(
+            record.id,
+            record.mastodon_async_data,
+            record.is_default,
+            tg_user_id,
+        )
+        .await
     }
 
-    async fn delete_login_user(&self, tg_user_id: UserId) -> anyhow::Result<()> {
+    async fn list_login_users(&self, tg_user_id: UserId) -> anyhow::Result<Vec<LoginUser>> {
         let tg_user_id_num = tg_user_id.0 as i64;
 
+        let records = sqlx::query!(
+            r#"
+SELECT id, mastodon_async_data, is_default
+FROM login_users
+WHERE tg_user_id = ?1
+ORDER BY id ASC
+        "#,
+            tg_user_id_num,
+        )
+        .fetch_all(self.inst_state.db.pool())
+        .await?;
+
+        let mut login_users = Vec::with_capacity(records.len());
+        for record in records {
+            login_users.push(
+                self.finish_loading_login_user(
+                    record.id,
+                    record.mastodon_async_data,
+                    record.is_default,
+                    tg_user_id,
+                )
+                .await?,
+            );
+        }
+        Ok(login_users)
+    }
+
+    /// Shared tail of every "load one row" query above: decrypts the row
+    /// (transparently re-encrypting legacy plaintext data) and assembles the
+    /// `LoginUser` along with its encoded handle.
+    async fn finish_loading_login_user(
+        &self,
+        account_id: i64,
+        mastodon_async_data: String,
+        is_default: bool,
+        tg_user_id: UserId,
+    ) -> anyhow::Result<LoginUser> {
+        let (data, was_legacy_plaintext) = decrypt_data(&mastodon_async_data)?;
+
+        if was_legacy_plaintext {
+            info!("tg user '{tg_user_id}' account '{account_id}' has plaintext login data, re-encrypting it");
+            self.reencrypt_login_user(account_id, &data).await?;
+        }
+
+        Ok(LoginUser {
+            inst: data.into(),
+            tg_user_id,
+            account_id,
+            handle: encode_handle(account_id)?,
+            is_default,
+        })
+    }
+
+    async fn delete_login_user(&self, account_id: i64) -> anyhow::Result<()> {
         _ = sqlx::query!(
             r#"
 DELETE FROM login_users
+WHERE id = ?1
+        "#,
+            account_id,
+        )
+        .execute(self.inst_state.db.pool())
+        .await?;
+
+        Ok(())
+    }
+
+    async fn load_settings(&self, tg_user_id: UserId) -> anyhow::Result<PostOptions> {
+        let tg_user_id_num = tg_user_id.0 as i64;
+
+        let record = sqlx::query!(
+            r#"
+SELECT visibility, language, spoiler_text, sensitive
+FROM user_settings
 WHERE tg_user_id = ?1
         "#,
             tg_user_id_num,
         )
+        .fetch_optional(self.inst_state.db.pool())
+        .await?;
+
+        Ok(match record {
+            Some(record) => PostOptions {
+                visibility: parse_visibility(&record.visibility).unwrap_or(Visibility::Public),
+                language: record.language,
+                spoiler_text: record.spoiler_text,
+                sensitive: record.sensitive != 0,
+            },
+            None => PostOptions::default(),
+        })
+    }
+
+    async fn save_settings(&self, tg_user_id: UserId, settings: &PostOptions) -> anyhow::Result<()> {
+        let tg_user_id_num = tg_user_id.0 as i64;
+        let visibility = visibility_str(settings.visibility);
+        let sensitive = settings.sensitive as i64;
+
+        sqlx::query!(
+            r#"
+INSERT INTO user_settings ( tg_user_id, visibility, language, spoiler_text, sensitive )
+VALUES ( ?1, ?2, ?3, ?4, ?5 )
+ON CONFLICT(tg_user_id) DO UPDATE SET
+    visibility = excluded.visibility,
+    language = excluded.language,
+    spoiler_text = excluded.spoiler_text,
+    sensitive = excluded.sensitive
+        "#,
+            tg_user_id_num,
+            visibility,
+            settings.language,
+            settings.spoiler_text,
+            sensitive,
+        )
         .execute(self.inst_state.db.pool())
         .await?;
 
@@ -115,40 +547,359 @@ WHERE tg_user_id = ?1
     }
 }
 
+/// A user's default posting preferences, resolved before every `post_status`
+/// call so per-invocation overrides remain possible without touching the
+/// stored defaults.
+pub struct PostOptions {
+    pub visibility: Visibility,
+    pub language: String,
+    pub spoiler_text: Option<String>,
+    pub sensitive: bool,
+}
+
+impl Default for PostOptions {
+    fn default() -> Self {
+        Self {
+            visibility: Visibility::Public,
+            language: "en".to_string(),
+            spoiler_text: None,
+            sensitive: false,
+        }
+    }
+}
+
+/// Whether `err` wraps SQLite's "database is locked" (`SQLITE_BUSY`, code
+/// `5`), the error a losing writer gets when it races another transaction.
+fn is_database_busy(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<sqlx::Error>()
+        .and_then(|err| err.as_database_error())
+        .is_some_and(|db_err| db_err.code().as_deref() == Some("5"))
+}
+
+fn parse_visibility(input: &str) -> anyhow::Result<Visibility> {
+    match input {
+        "public" => Ok(Visibility::Public),
+        "unlisted" => Ok(Visibility::Unlisted),
+        "private" => Ok(Visibility::Private),
+        "direct" => Ok(Visibility::Direct),
+        other => Err(anyhow!("unknown visibility '{other}', expected public/unlisted/private/direct")),
+    }
+}
+
+fn visibility_str(visibility: Visibility) -> &'static str {
+    match visibility {
+        Visibility::Public => "public",
+        Visibility::Unlisted => "unlisted",
+        Visibility::Private => "private",
+        Visibility::Direct => "direct",
+    }
+}
+
+/// However small `config::status_char_limit()` is misconfigured to, never
+/// split narrower than this. Below it there isn't enough room left for the
+/// "(n/m)" suffix (or even an ordinary word) without cutting mid-word, and a
+/// limit that collapses to 0 after reserving suffix room would leave
+/// `split_into_chunks` cutting one-character chunks.
+const MIN_STATUS_CHAR_LIMIT: usize = 40;
+
+/// Splits `text` into status-sized chunks for a self-threaded reply chain,
+/// never breaking mid-word. When `number_threads` is set and more than one
+/// chunk is needed, each chunk is suffixed with a `(n/m)` counter, with room
+/// for it reserved up front so the suffixed chunk still fits `limit`, except
+/// when `limit` is so close to `MIN_STATUS_CHAR_LIMIT` that no room is left.
+fn split_status_text(text: &str, limit: usize, number_threads: bool) -> Vec<String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return vec![String::new()];
+    }
+    let limit = limit.max(MIN_STATUS_CHAR_LIMIT);
+
+    let mut chunks = split_into_chunks(text, limit);
+    if number_threads && chunks.len() > 1 {
+        // Reserving room for the "(n/m)" suffix can itself push the chunk
+        // count across a digit boundary (e.g. 9 -> 10), which widens the
+        // suffix again. Re-split until the count (and thus the suffix width)
+        // stops changing.
+        loop {
+            let suffix_len = format!(" ({}/{})", chunks.len(), chunks.len()).len();
+            // Never resplit narrower than the floor itself: if `limit` is
+            // within a suffix's width of `MIN_STATUS_CHAR_LIMIT`, a suffixed
+            // chunk may end up over budget by up to the suffix's own width,
+            // but that beats violating the floor.
+            let reduced_limit = limit.saturating_sub(suffix_len).max(MIN_STATUS_CHAR_LIMIT);
+            let resplit = split_into_chunks(text, reduced_limit);
+            let converged = resplit.len() == chunks.len();
+            chunks = resplit;
+            if converged {
+                break;
+            }
+        }
+
+        let total = chunks.len();
+        chunks = chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, chunk)| format!("{chunk} ({}/{total})", i + 1))
+            .collect();
+    }
+
+    chunks
+}
+
+fn split_into_chunks(text: &str, limit: usize) -> Vec<String> {
+    // A limit of 0 would never make progress below (find_split_point would
+    // keep returning byte offset 0), so guard against it defensively even
+    // though split_status_text's floor keeps real callers well above this.
+    let limit = limit.max(1);
+    let mut remaining = text;
+    let mut chunks = Vec::new();
+
+    while !remaining.is_empty() {
+        if remaining.chars().count() <= limit {
+            chunks.push(remaining.to_string());
+            break;
+        }
+
+        let split_at = find_split_point(remaining, limit).max(1);
+        let (chunk, rest) = remaining.split_at(split_at);
+        chunks.push(chunk.trim_end().to_string());
+        remaining = rest.trim_start();
+    }
+
+    chunks
+}
+
+/// Finds the byte offset at or before `limit` chars to break `text`,
+/// preferring a paragraph break, then a sentence end, then whitespace, and
+/// only cutting mid-word as a last resort (a single word longer than the
+/// limit).
+fn find_split_point(text: &str, limit: usize) -> usize {
+    let limit_byte = text
+        .char_indices()
+        .nth(limit)
+        .map(|(idx, _)| idx)
+        .unwrap_or(text.len());
+    let window = &text[..limit_byte];
+
+    if let Some(pos) = window.rfind("\n\n") {
+        return pos + 2;
+    }
+    if let Some(pos) = window.rfind(". ") {
+        return pos + 2;
+    }
+    if let Some((pos, ch)) = window.char_indices().rev().find(|(_, ch)| ch.is_whitespace()) {
+        return pos + ch.len_utf8();
+    }
+
+    limit_byte
+}
+
+/// Mastodon's own cap on how many media files a single status may carry.
+const MAX_ATTACHMENTS_PER_STATUS: usize = 4;
+
+/// A media file downloaded from the replied-to Telegram message, ready to be
+/// uploaded to Mastodon and attached to a status.
+pub struct Attachment {
+    pub bytes: Vec<u8>,
+    pub file_name: String,
+    pub mime_type: String,
+    pub description: Option<String>,
+}
+
 pub struct LoginUser {
     inst: Mastodon,
     tg_user_id: UserId,
+    account_id: i64,
+    handle: String,
+    is_default: bool,
 }
 
 impl LoginUser {
     pub fn domain(&self) -> &str {
         &self.inst.data.base
     }
-    pub async fn post_status(&self, text: impl Into<String>) -> anyhow::Result<String> {
-        let status = StatusBuilder::new()
-            .status(text)
-            .visibility(Visibility::Public)
-            .language(Language::Eng)
-            .build()?;
 
-        let posted = self.inst.new_status(status).await?;
-        let url = posted.url.unwrap_or_else(|| "*invisible*".to_string());
+    /// Short opaque handle (e.g. `8h2Kq1`) a user can pass to `/post`,
+    /// `/revoke` or `/accounts` to address this specific linked account.
+    pub fn handle(&self) -> &str {
+        &self.handle
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.is_default
+    }
+
+    /// Posts `text` to Mastodon, splitting it into a self-threaded reply
+    /// chain when it exceeds the instance's status length limit. Returns the
+    /// URL of the root (first) status; any media attachments land on it.
+    pub async fn post_status(
+        &self,
+        text: impl Into<String>,
+        attachments: Vec<Attachment>,
+        options: &PostOptions,
+    ) -> anyhow::Result<String> {
+        let text = text.into();
+        let media_ids = self.upload_attachments(attachments).await;
+
+        let language = options.language.parse::<Language>().unwrap_or_else(|_| {
+            warn!(
+                "tg user '{}' has invalid language code '{}', falling back to English",
+                self.tg_user_id, options.language
+            );
+            Language::Eng
+        });
+
+        let chunks = split_status_text(
+            &text,
+            config::status_char_limit(),
+            config::status_thread_numbering(),
+        );
+
+        let mut root_url = None;
+        let mut in_reply_to_id = None;
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut builder = StatusBuilder::new();
+            builder
+                .status(chunk)
+                .visibility(options.visibility)
+                .language(language.clone())
+                .sensitive(options.sensitive);
+            if let Some(spoiler_text) = &options.spoiler_text {
+                builder.spoiler_text(spoiler_text);
+            }
+            if i == 0 && !media_ids.is_empty() {
+                builder.media_ids(media_ids.clone());
+            }
+            if let Some(in_reply_to_id) = &in_reply_to_id {
+                builder.in_reply_to_id(in_reply_to_id);
+            }
+
+            let status = builder.build()?;
+            let posted = self.inst.new_status(status).await?;
+
+            in_reply_to_id = Some(posted.id.to_string());
+            if i == 0 {
+                root_url = Some(posted.url.unwrap_or_else(|| "*invisible*".to_string()));
+            }
+        }
 
-        info!("tg user '{}' status posted: {url}", self.tg_user_id);
+        let url = root_url.expect("split_status_text always yields at least one chunk");
+        info!(
+            "tg user '{}' status posted as a {}-part thread: {url}",
+            self.tg_user_id,
+            chunks.len()
+        );
         Ok(url)
     }
+
+    /// Uploads each attachment to Mastodon, logging and skipping individual
+    /// failures rather than aborting the whole post. Drops any attachments
+    /// past Mastodon's per-status limit, logging a warning when it does.
+    async fn upload_attachments(&self, attachments: Vec<Attachment>) -> Vec<String> {
+        if attachments.len() > MAX_ATTACHMENTS_PER_STATUS {
+            warn!(
+                "tg user '{}' attached {} files, only the first {MAX_ATTACHMENTS_PER_STATUS} will be posted",
+                self.tg_user_id,
+                attachments.len()
+            );
+        }
+
+        let mut media_ids = Vec::with_capacity(MAX_ATTACHMENTS_PER_STATUS);
+        for attachment in attachments.into_iter().take(MAX_ATTACHMENTS_PER_STATUS) {
+            match self.upload_attachment(attachment).await {
+                Ok(media_id) => media_ids.push(media_id),
+                Err(err) => warn!(
+                    "tg user '{}' failed to upload an attachment, skipping it: {err}",
+                    self.tg_user_id
+                ),
+            }
+        }
+        media_ids
+    }
+
+    async fn upload_attachment(&self, attachment: Attachment) -> anyhow::Result<String> {
+        let media = self
+            .inst
+            .media(attachment.bytes, attachment.file_name, Some(attachment.mime_type))
+            .await?;
+
+        if let Some(description) = attachment.description {
+            self.inst
+                .update_media(&media.id, Some(description), None, None, None)
+                .await?;
+        }
+
+        Ok(media.id.to_string())
+    }
 }
 
-impl LoginUser {
-    fn serialize(&self) -> String {
-        json::to_string(&self.inst.data).unwrap()
+/// Encrypts the underlying `mastodon_async` `Data` (which holds the live
+/// OAuth token) with a fresh random nonce and returns it as
+/// `base64(nonce || ciphertext)`, ready to store in `login_users`.
+fn encrypt_data(data: &Data) -> anyhow::Result<String> {
+    let plaintext = json::to_string(data)?;
+    let cipher = XSalsa20Poly1305::new(&secret_key()?);
+
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce_bytes);
+    let nonce = Nonce::from_slice(&nonce_bytes);
+
+    let ciphertext = cipher
+        .encrypt(nonce, plaintext.as_bytes())
+        .map_err(|_| anyhow!("failed to encrypt login data"))?;
+
+    let mut payload = nonce_bytes.to_vec();
+    payload.extend(ciphertext);
+    Ok(B64.encode(payload))
+}
+
+/// Decrypts `input`, transparently accepting legacy plaintext-JSON rows
+/// written before encryption was introduced. Returns whether the row was
+/// legacy plaintext so the caller can re-encrypt it right away.
+fn decrypt_data(input: &str) -> anyhow::Result<(Data, bool)> {
+    match decrypt(input) {
+        Ok(data) => Ok((data, false)),
+        Err(_) if input.trim_start().starts_with('{') => Ok((json::from_str(input)?, true)),
+        Err(err) => Err(err),
     }
+}
 
-    fn deserialize(input: impl AsRef<str>, tg_user_id: UserId) -> anyhow::Result<Self> {
-        let data: Data = json::from_str(input.as_ref())?;
-        Ok(Self {
-            inst: data.into(),
-            tg_user_id,
-        })
+fn decrypt(input: &str) -> anyhow::Result<Data> {
+    let payload = B64.decode(input).map_err(|_| ReauthRequired)?;
+    if payload.len() < NONCE_LEN {
+        return Err(ReauthRequired.into());
     }
+    let (nonce_bytes, ciphertext) = payload.split_at(NONCE_LEN);
+
+    let cipher = XSalsa20Poly1305::new(&secret_key()?);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce_bytes), ciphertext)
+        .map_err(|_| ReauthRequired)?;
+
+    Ok(json::from_slice(&plaintext)?)
+}
+
+fn secret_key() -> anyhow::Result<Key> {
+    Ok(*Key::from_slice(&config::login_data_secret_key()?))
+}
+
+/// Codec for the short opaque handles (`sqids`-encoded account ids) used in
+/// command arguments instead of raw database ids.
+fn handle_codec() -> Sqids {
+    Sqids::default()
+}
+
+fn encode_handle(account_id: i64) -> anyhow::Result<String> {
+    handle_codec()
+        .encode(&[account_id as u64])
+        .map_err(|err| anyhow!("failed to encode account handle: {err}"))
+}
+
+fn decode_handle(handle: &str) -> anyhow::Result<i64> {
+    handle_codec()
+        .decode(handle)
+        .first()
+        .map(|&id| id as i64)
+        .ok_or_else(|| anyhow!("unknown account handle '{handle}'"))
 }